@@ -1,59 +1,236 @@
 #![allow(unstable)]
 
+use std::marker::PhantomData;
+use std::iter::FromIterator;
+
+pub trait Positioner: Clone {
+    type Item;
+    fn start() -> Self;
+    fn update(&mut self, item: &<Self as Positioner>::Item);
+}
+
 #[derive(Clone, Show, PartialEq)]
-pub struct Error;
+pub struct SourcePosition {
+    pub line: i32,
+    pub column: i32
+}
 
-pub type ParseResult<O, I> = Result<(O, I), Error>;
+impl Positioner for SourcePosition {
+    type Item = char;
+    fn start() -> SourcePosition {
+        SourcePosition { line: 1, column: 1 }
+    }
+    fn update(&mut self, item: &char) {
+        if *item == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+#[derive(Clone, Show, PartialEq)]
+pub struct IndexPosition {
+    pub index: i32
+}
+
+impl <T> Positioner for IndexPosition {
+    type Item = T;
+    fn start() -> IndexPosition {
+        IndexPosition { index: 0 }
+    }
+    fn update(&mut self, _item: &T) {
+        self.index += 1;
+    }
+}
 
+#[derive(Clone, Show, PartialEq)]
+pub enum ErrorInfo<Item> {
+    Unexpected(Item),
+    Expected(String),
+    Message(String)
+}
+
+#[derive(Clone, Show, PartialEq)]
+pub struct ParseError<P, Item> {
+    pub position: P,
+    pub errors: Vec<ErrorInfo<Item>>
+}
+
+impl <P, Item> ParseError<P, Item> {
+    pub fn new(position: P, error: ErrorInfo<Item>) -> ParseError<P, Item> {
+        ParseError { position: position, errors: vec![error] }
+    }
+
+    pub fn empty(position: P) -> ParseError<P, Item> {
+        ParseError { position: position, errors: Vec::new() }
+    }
+
+    pub fn add_error(&mut self, error: ErrorInfo<Item>) {
+        self.errors.push(error);
+    }
+}
+
+impl <P: PartialEq, Item> ParseError<P, Item> {
+    pub fn merge(mut self, other: ParseError<P, Item>) -> ParseError<P, Item> {
+        if self.position == other.position {
+            self.errors.extend(other.errors.into_iter());
+            self
+        } else {
+            other
+        }
+    }
+}
 
-pub trait Stream {
+pub type PError<I> = ParseError<<I as Stream>::Position, <I as Stream>::Item>;
+
+#[derive(Clone, Show, PartialEq)]
+pub enum Consumed<T> {
+    Consumed(T),
+    Empty(T)
+}
+
+pub use self::Consumed::{Consumed, Empty};
+
+impl <T> Consumed<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Consumed(t) => t,
+            Empty(t) => t
+        }
+    }
+
+    pub fn is_consumed(&self) -> bool {
+        match *self {
+            Consumed(_) => true,
+            Empty(_) => false
+        }
+    }
+
+    pub fn map<U, F>(self, f: F) -> Consumed<U>
+        where F: FnOnce(T) -> U {
+        match self {
+            Consumed(t) => Consumed(f(t)),
+            Empty(t) => Empty(f(t))
+        }
+    }
+}
+
+pub type ParseResult<O, I> = Result<Consumed<(O, I)>, Consumed<PError<I>>>;
+
+
+pub trait Stream: Sized {
     type Item;
+    type Position: Clone + PartialEq;
+    fn position(&self) -> <Self as Stream>::Position;
     fn uncons(self) -> ParseResult<<Self as Stream>::Item, Self>;
 }
 
 impl <I: Iterator> Stream for I {
     type Item = <I as Iterator>::Item;
+    type Position = ();
+    fn position(&self) -> () { () }
     fn uncons(mut self) -> ParseResult<<I as Iterator>::Item, I> {
+        let position = self.position();
         match self.next() {
-            Some(x) => Ok((x, self)),
-            None => Err(Error)
+            Some(x) => Ok(Consumed((x, self))),
+            None => Err(Empty(ParseError::new(position, ErrorInfo::Message("end of input".to_string()))))
         }
     }
 }
 
 impl <'a> Stream for &'a str {
     type Item = char;
+    type Position = ();
+    fn position(&self) -> () { () }
     fn uncons(self) -> ParseResult<char, &'a str> {
         match self.slice_shift_char() {
-            Some(x) => Ok(x),
-            None => Err(Error)
+            Some(x) => Ok(Consumed(x)),
+            None => Err(Empty(ParseError::new(self.position(), ErrorInfo::Message("end of input".to_string()))))
         }
     }
 }
 
 impl <'a, T> Stream for &'a [T] {
     type Item = &'a T;
+    type Position = ();
+    fn position(&self) -> () { () }
     fn uncons(self) -> ParseResult<&'a T, &'a [T]> {
         match self {
-            [ref x, rest..] => Ok((x, rest)),
-            [] => Err(Error)
+            [ref x, rest..] => Ok(Consumed((x, rest))),
+            [] => Err(Empty(ParseError::new(self.position(), ErrorInfo::Message("end of input".to_string()))))
+        }
+    }
+}
+
+#[derive(Clone, Show, PartialEq)]
+pub struct State<I, P> {
+    pub input: I,
+    pub position: P
+}
+
+impl <I, P> State<I, P>
+    where I: Stream, P: Positioner<Item=<I as Stream>::Item> {
+    pub fn new(input: I) -> State<I, P> {
+        State { input: input, position: Positioner::start() }
+    }
+}
+
+impl <I, P> Stream for State<I, P>
+    where I: Stream, P: Positioner<Item=<I as Stream>::Item> + Clone {
+    type Item = <I as Stream>::Item;
+    type Position = P;
+    fn position(&self) -> P { self.position.clone() }
+    fn uncons(self) -> ParseResult<<I as Stream>::Item, State<I, P>> {
+        let State { input, position } = self;
+        match input.uncons() {
+            Ok(Consumed((item, rest))) => {
+                let mut position = position;
+                position.update(&item);
+                Ok(Consumed((item, State { input: rest, position: position })))
+            }
+            Err(_) => Err(Empty(ParseError::new(position, ErrorInfo::Message("end of input".to_string()))))
         }
     }
 }
 
 
+#[derive(Clone, Show, PartialEq)]
+pub enum Representation {
+    StringTerminal(String),
+    CharClass(String),
+    Nonterminal(String),
+    // Carries the named rule's own body, so a renderer can walk into it and emit a
+    // production for the name instead of treating it as a dangling reference.
+    Named(String, Box<Representation>),
+    Sequence(Vec<Representation>),
+    Choice(Vec<Representation>),
+    Repeat0(Box<Representation>),
+    Repeat1(Box<Representation>),
+    Optional(Box<Representation>),
+    SepBy(Box<Representation>, Box<Representation>)
+}
+
 pub trait Parser {
     type Input: Clone + Stream;
     type Output;
     fn parse(&mut self, input: <Self as Parser>::Input) -> ParseResult<<Self as Parser>::Output, <Self as Parser>::Input>;
+
+    fn representation(&self) -> Representation {
+        Representation::Nonterminal("?".to_string())
+    }
 }
-impl <'a, I, O, P> Parser for &'a mut P 
+impl <'a, I, O, P> Parser for &'a mut P
     where I: Clone + Stream, P: Parser<Input=I, Output=O> {
     type Input = I;
     type Output = O;
     fn parse(&mut self, input: I) -> ParseResult<O, I> {
         (*self).parse(input)
     }
+    fn representation(&self) -> Representation {
+        (**self).representation()
+    }
 }
 
 pub fn char<'a, I>(input: I) -> ParseResult<char, I>
@@ -69,16 +246,22 @@ impl <'a, O, P: Parser<Output=O> + 'a> Parser for ManyAppend<'a, O, P> {
     type Input = <P as Parser>::Input;
     type Output = ();
     fn parse(&mut self, mut input: <P as Parser>::Input) -> ParseResult<(), <P as Parser>::Input> {
+        let mut consumed = false;
         loop {
             match self.parser.parse(input.clone()) {
-                Ok((x, rest)) => {
+                Ok(Consumed((x, rest))) => {
                     self.vec.push(x);
                     input = rest;
+                    consumed = true;
                 }
-                Err(_) => break
+                // A parser that succeeds without consuming would loop forever; stop instead
+                // of including its (empty) result again and again.
+                Ok(Empty(_)) => break,
+                Err(Consumed(err)) => return Err(Consumed(err)),
+                Err(Empty(_)) => break
             }
         }
-        Ok(((), input))
+        if consumed { Ok(Consumed(((), input))) } else { Ok(Empty(((), input))) }
     }
 }
 
@@ -86,64 +269,139 @@ pub fn many_append<'a, O, P: Parser<Output=O>>(parser: P, vec: &'a mut Vec<O>) -
     ManyAppend { parser: parser, vec: vec }
 }
 
-#[derive(Clone)]
-pub struct Many<P> {
-    parser: P
+pub struct Many<F, P> {
+    parser: P,
+    _marker: PhantomData<F>
+}
+impl <F, P: Clone> Clone for Many<F, P> {
+    fn clone(&self) -> Many<F, P> {
+        Many { parser: self.parser.clone(), _marker: PhantomData }
+    }
 }
-impl <P: Parser> Parser for Many<P> {
+impl <F, P: Parser> Parser for Many<F, P>
+    where F: FromIterator<<P as Parser>::Output> {
     type Input = <P as Parser>::Input;
-    type Output = Vec<<P as Parser>::Output>;
-    fn parse(&mut self, input: <P as Parser>::Input) -> ParseResult<Vec<<P as Parser>::Output>, <P as Parser>::Input> {
+    type Output = F;
+    fn parse(&mut self, input: <P as Parser>::Input) -> ParseResult<F, <P as Parser>::Input> {
         let mut result = Vec::new();
-        let ((), input) = try!(many_append(&mut self.parser, &mut result).parse(input));
-        Ok((result, input))
+        match try!(many_append(&mut self.parser, &mut result).parse(input)) {
+            Consumed(((), input)) => Ok(Consumed((result.into_iter().collect(), input))),
+            Empty(((), input)) => Ok(Empty((result.into_iter().collect(), input)))
+        }
+    }
+    fn representation(&self) -> Representation {
+        Representation::Repeat0(Box::new(self.parser.representation()))
     }
 }
-pub fn many<P: Parser>(p: P) -> Many<P> {
-    Many { parser: p }
+pub fn many<F, P: Parser>(p: P) -> Many<F, P>
+    where F: FromIterator<<P as Parser>::Output> {
+    Many { parser: p, _marker: PhantomData }
 }
 
-pub fn many1<'a, P: Clone + 'a>(mut p: P) -> Box<Parser<Input=<P as Parser>::Input, Output=Vec<<P as Parser>::Output>> + 'a>
-    where P: Parser {
-    Box::new(FnParser(move |&mut:input| {
-        let (first, input) = try!(p.parse(input));
-        let mut result = vec![first];
-        let ((), input) = try!(many_append(&mut p, &mut result).parse(input));
-        Ok((result, input))
-    }))
+pub struct Many1<F, P> {
+    parser: P,
+    _marker: PhantomData<F>
+}
+impl <F, P: Clone> Clone for Many1<F, P> {
+    fn clone(&self) -> Many1<F, P> {
+        Many1 { parser: self.parser.clone(), _marker: PhantomData }
+    }
+}
+impl <F, P: Parser> Parser for Many1<F, P>
+    where F: FromIterator<<P as Parser>::Output> {
+    type Input = <P as Parser>::Input;
+    type Output = F;
+    fn parse(&mut self, input: <P as Parser>::Input) -> ParseResult<F, <P as Parser>::Input> {
+        match self.parser.parse(input) {
+            Ok(Consumed((first, input))) => {
+                let mut result = vec![first];
+                match try!(many_append(&mut self.parser, &mut result).parse(input)) {
+                    Consumed(((), input)) => Ok(Consumed((result.into_iter().collect(), input))),
+                    Empty(((), input)) => Ok(Consumed((result.into_iter().collect(), input)))
+                }
+            }
+            Ok(Empty((first, input))) => {
+                let mut result = vec![first];
+                match try!(many_append(&mut self.parser, &mut result).parse(input)) {
+                    Consumed(((), input)) => Ok(Consumed((result.into_iter().collect(), input))),
+                    Empty(((), input)) => Ok(Empty((result.into_iter().collect(), input)))
+                }
+            }
+            Err(err) => Err(err)
+        }
+    }
+    fn representation(&self) -> Representation {
+        Representation::Repeat1(Box::new(self.parser.representation()))
+    }
+}
+pub fn many1<F, P: Parser>(p: P) -> Many1<F, P>
+    where F: FromIterator<<P as Parser>::Output> {
+    Many1 { parser: p, _marker: PhantomData }
 }
 
-#[derive(Clone)]
-pub struct SepBy<P, S> {
+pub struct SepBy<F, P, S> {
     parser: P,
-    separator: S
+    separator: S,
+    _marker: PhantomData<F>
+}
+impl <F, P: Clone, S: Clone> Clone for SepBy<F, P, S> {
+    fn clone(&self) -> SepBy<F, P, S> {
+        SepBy { parser: self.parser.clone(), separator: self.separator.clone(), _marker: PhantomData }
+    }
 }
-impl <P, S> Parser for SepBy<P, S>
-    where P: Parser, S: Parser<Input=<P as Parser>::Input> {
+impl <F, P, S> Parser for SepBy<F, P, S>
+    where P: Parser, S: Parser<Input=<P as Parser>::Input>, F: FromIterator<<P as Parser>::Output> {
 
     type Input = <P as Parser>::Input;
-    type Output = Vec<<P as Parser>::Output>;
-    fn parse(&mut self, mut input: <P as Parser>::Input) -> ParseResult<Vec<<P as Parser>::Output>, <P as Parser>::Input> {
+    type Output = F;
+    fn parse(&mut self, mut input: <P as Parser>::Input) -> ParseResult<F, <P as Parser>::Input> {
         let mut result = Vec::new();
+        let mut consumed = false;
         match self.parser.parse(input.clone()) {
-            Ok((x, rest)) => {
+            Ok(Consumed((x, rest))) => {
                 result.push(x);
                 input = rest;
+                consumed = true;
             }
-            Err(_) => return Ok((result, input))
+            Ok(Empty((x, rest))) => {
+                result.push(x);
+                input = rest;
+            }
+            Err(Consumed(err)) => return Err(Consumed(err)),
+            Err(Empty(_)) => return Ok(Empty((result.into_iter().collect(), input)))
         }
-        let rest = FnParser(|input| {
-            let mut env = Env::new(input);
-            try!(env.with(&mut self.separator));
-            let v = try!(env.with(&mut self.parser));
-            env.result(v)
-        });
-        let ((), input) = try!(many_append(rest, &mut result).parse(input));
-        Ok((result, input))
+        loop {
+            match self.separator.parse(input.clone()) {
+                Ok(Consumed((_, rest))) => {
+                    consumed = true;
+                    match self.parser.parse(rest) {
+                        Ok(Consumed((x, rest))) => { result.push(x); input = rest; }
+                        Ok(Empty((x, rest))) => { result.push(x); input = rest; }
+                        // The separator committed us to another item, so a failure here
+                        // is a hard error, not a reason to stop the loop quietly.
+                        Err(err) => return Err(Consumed(err.into_inner()))
+                    }
+                }
+                Ok(Empty((_, rest))) => {
+                    match self.parser.parse(rest) {
+                        Ok(Consumed((x, rest))) => { result.push(x); input = rest; consumed = true; }
+                        Ok(Empty((x, rest))) => { result.push(x); input = rest; }
+                        Err(_) => break
+                    }
+                }
+                Err(Consumed(err)) => return Err(Consumed(err)),
+                Err(Empty(_)) => break
+            }
+        }
+        if consumed { Ok(Consumed((result.into_iter().collect(), input))) } else { Ok(Empty((result.into_iter().collect(), input))) }
+    }
+    fn representation(&self) -> Representation {
+        Representation::SepBy(Box::new(self.parser.representation()), Box::new(self.separator.representation()))
     }
 }
-pub fn sep_by<P: Parser, S: Parser>(parser: P, separator: S) -> SepBy<P, S> {
-    SepBy { parser: parser, separator: separator }
+pub fn sep_by<F, P: Parser, S: Parser>(parser: P, separator: S) -> SepBy<F, P, S>
+    where F: FromIterator<<P as Parser>::Output> {
+    SepBy { parser: parser, separator: separator, _marker: PhantomData }
 }
 
 
@@ -155,6 +413,15 @@ impl <'a, I: Clone + Stream, O> Parser for Box<FnMut(I) -> ParseResult<O, I> + '
     }
 }
 
+impl <'a, I, O> Parser for Box<Parser<Input=I, Output=O> + 'a>
+    where I: Clone + Stream {
+    type Input = I;
+    type Output = O;
+    fn parse(&mut self, input: I) -> ParseResult<O, I> {
+        (**self).parse(input)
+    }
+}
+
 #[derive(Clone)]
 struct FnParser<'a, I: Stream, O, F: FnMut(I) -> ParseResult<O, I>>(F);
 
@@ -177,7 +444,7 @@ impl <'a, I, O> Parser for fn (I) -> ParseResult<O, I>
 }
 
 #[derive(Clone)]
-pub struct Satisfy<I, Pred> { pred: Pred }
+pub struct Satisfy<I, Pred> { pred: Pred, name: &'static str }
 
 impl <'a, I, Pred> Parser for Satisfy<I, Pred>
     where I: Stream<Item=char> + Clone, Pred: FnMut(char) -> bool {
@@ -185,24 +452,35 @@ impl <'a, I, Pred> Parser for Satisfy<I, Pred>
     type Input = I;
     type Output = char;
     fn parse(&mut self, input: I) -> ParseResult<char, I> {
+        let position = input.position();
         match input.uncons() {
-            Ok((c, s)) => {
-                if (self.pred)(c) { Ok((c, s)) }
-                else { Err(Error) }
+            // A predicate failure is a token-level failure: it never consumed anything,
+            // so `or` can still try another branch without needing `try`.
+            Ok(Consumed((c, s))) => {
+                if (self.pred)(c) { Ok(Consumed((c, s))) }
+                else { Err(Empty(ParseError::new(position, ErrorInfo::Unexpected(c)))) }
             }
             Err(err) => Err(err)
         }
     }
+    fn representation(&self) -> Representation {
+        Representation::CharClass(self.name.to_string())
+    }
+}
+
+fn satisfy_named<I, Pred>(pred: Pred, name: &'static str) -> Satisfy<I, Pred>
+    where I: Stream + Clone, Pred: FnMut(char) -> bool {
+    Satisfy { pred: pred, name: name }
 }
 
 pub fn satisfy<I, Pred>(pred: Pred) -> Satisfy<I, Pred>
     where I: Stream + Clone, Pred: FnMut(char) -> bool {
-    Satisfy { pred: pred }
+    satisfy_named(pred, "char")
 }
 
 pub fn space<I>() -> Satisfy<I, fn (char) -> bool>
     where I: Stream + Clone {
-    satisfy(CharExt::is_whitespace as fn (char) -> bool)
+    satisfy_named(CharExt::is_whitespace as fn (char) -> bool, "space")
 }
 
 #[derive(Clone)]
@@ -212,16 +490,27 @@ impl <'a, 'b, I> Parser for StringP<'b, I>
     type Input = I;
     type Output = &'b str;
     fn parse(&mut self, mut input: I) -> ParseResult<&'b str, I> {
+        let mut consumed = false;
         for c in self.s.chars() {
+            let position = input.position();
             match input.uncons() {
-                Ok((other, rest)) => {
-                    if c != other { return Err(Error);  }
+                Ok(Consumed((other, rest))) => {
+                    if c != other {
+                        let err = ParseError::new(position, ErrorInfo::Expected(format!("{}", self.s)));
+                        return if consumed { Err(Consumed(err)) } else { Err(Empty(err)) };
+                    }
                     input = rest;
+                    consumed = true;
+                }
+                Err(err) => {
+                    return if consumed { Err(Consumed(err.into_inner())) } else { Err(err) };
                 }
-                Err(err) => return Err(err)
             }
         }
-        Ok((self.s, input))
+        if consumed { Ok(Consumed((self.s, input))) } else { Ok(Empty((self.s, input))) }
+    }
+    fn representation(&self) -> Representation {
+        Representation::StringTerminal(self.s.to_string())
     }
 }
 
@@ -238,9 +527,30 @@ impl <I, A, B, P1, P2> Parser for AndThen<P1, P2>
     type Input = I;
     type Output = (A, B);
     fn parse(&mut self, input: I) -> ParseResult<(A, B), I> {
-        let (a, rest) = try!(self.0.parse(input));
-        let (b, rest) = try!(self.1.parse(rest));
-        Ok(((a, b), rest))
+        match self.0.parse(input) {
+            Ok(Consumed((a, rest))) => {
+                // The first parser already consumed, so the whole sequence has too,
+                // no matter what the second one does.
+                match self.1.parse(rest) {
+                    Ok(Consumed((b, rest))) => Ok(Consumed(((a, b), rest))),
+                    Ok(Empty((b, rest))) => Ok(Consumed(((a, b), rest))),
+                    Err(Consumed(err)) => Err(Consumed(err)),
+                    Err(Empty(err)) => Err(Consumed(err))
+                }
+            }
+            Ok(Empty((a, rest))) => {
+                match self.1.parse(rest) {
+                    Ok(Consumed((b, rest))) => Ok(Consumed(((a, b), rest))),
+                    Ok(Empty((b, rest))) => Ok(Empty(((a, b), rest))),
+                    Err(Consumed(err)) => Err(Consumed(err)),
+                    Err(Empty(err)) => Err(Empty(err))
+                }
+            }
+            Err(err) => Err(err)
+        }
+    }
+    fn representation(&self) -> Representation {
+        Representation::Sequence(vec![self.0.representation(), self.1.representation()])
     }
 }
 pub fn and_then<P1, P2>(p1: P1, p2: P2) -> AndThen<P1, P2>
@@ -248,6 +558,150 @@ pub fn and_then<P1, P2>(p1: P1, p2: P2) -> AndThen<P1, P2>
     AndThen(p1, p2)
 }
 
+#[derive(Clone)]
+pub struct Map<P, F>(P, F);
+impl <P, F, B> Parser for Map<P, F>
+    where P: Parser, F: FnMut(<P as Parser>::Output) -> B {
+
+    type Input = <P as Parser>::Input;
+    type Output = B;
+    fn parse(&mut self, input: <P as Parser>::Input) -> ParseResult<B, <P as Parser>::Input> {
+        match self.0.parse(input) {
+            Ok(Consumed((x, rest))) => Ok(Consumed(((self.1)(x), rest))),
+            Ok(Empty((x, rest))) => Ok(Empty(((self.1)(x), rest))),
+            Err(err) => Err(err)
+        }
+    }
+}
+pub fn map<P, F, B>(parser: P, f: F) -> Map<P, F>
+    where P: Parser, F: FnMut(<P as Parser>::Output) -> B {
+    Map(parser, f)
+}
+
+#[derive(Clone)]
+pub struct Then<P, F>(P, F);
+impl <P, F, P2> Parser for Then<P, F>
+    where P: Parser, F: FnMut(<P as Parser>::Output) -> P2, P2: Parser<Input=<P as Parser>::Input> {
+
+    type Input = <P as Parser>::Input;
+    type Output = <P2 as Parser>::Output;
+    fn parse(&mut self, input: <P as Parser>::Input) -> ParseResult<<P2 as Parser>::Output, <P as Parser>::Input> {
+        match self.0.parse(input) {
+            Ok(Consumed((x, rest))) => {
+                match (self.1)(x).parse(rest) {
+                    Ok(Consumed((y, rest))) => Ok(Consumed((y, rest))),
+                    Ok(Empty((y, rest))) => Ok(Consumed((y, rest))),
+                    Err(Consumed(err)) => Err(Consumed(err)),
+                    Err(Empty(err)) => Err(Consumed(err))
+                }
+            }
+            Ok(Empty((x, rest))) => {
+                match (self.1)(x).parse(rest) {
+                    Ok(Consumed((y, rest))) => Ok(Consumed((y, rest))),
+                    Ok(Empty((y, rest))) => Ok(Empty((y, rest))),
+                    Err(Consumed(err)) => Err(Consumed(err)),
+                    Err(Empty(err)) => Err(Empty(err))
+                }
+            }
+            Err(err) => Err(err)
+        }
+    }
+}
+pub fn then<P, F, P2>(parser: P, f: F) -> Then<P, F>
+    where P: Parser, F: FnMut(<P as Parser>::Output) -> P2, P2: Parser<Input=<P as Parser>::Input> {
+    Then(parser, f)
+}
+
+#[derive(Clone)]
+pub struct Or<P1, P2>(P1, P2);
+impl <I, O, P1, P2> Parser for Or<P1, P2>
+    where I: Clone + Stream, P1: Parser<Input=I, Output=O>, P2: Parser<Input=I, Output=O> {
+
+    type Input = I;
+    type Output = O;
+    fn parse(&mut self, input: I) -> ParseResult<O, I> {
+        match self.0.parse(input.clone()) {
+            Ok(x) => Ok(x),
+            Err(Consumed(err)) => Err(Consumed(err)),
+            Err(Empty(err1)) => {
+                match self.1.parse(input) {
+                    Ok(x) => Ok(x),
+                    Err(Consumed(err2)) => Err(Consumed(err2)),
+                    Err(Empty(err2)) => Err(Empty(err1.merge(err2)))
+                }
+            }
+        }
+    }
+    fn representation(&self) -> Representation {
+        Representation::Choice(vec![self.0.representation(), self.1.representation()])
+    }
+}
+pub fn or<P1, P2>(p1: P1, p2: P2) -> Or<P1, P2>
+    where P1: Parser, P2: Parser<Input=<P1 as Parser>::Input, Output=<P1 as Parser>::Output> {
+    Or(p1, p2)
+}
+
+pub trait Choice {
+    type Input: Clone + Stream;
+    type Output;
+    fn choice_parse(&mut self, input: <Self as Choice>::Input) -> ParseResult<<Self as Choice>::Output, <Self as Choice>::Input>;
+    fn choice_representation(&self) -> Vec<Representation>;
+}
+
+macro_rules! tuple_choice {
+    ($head:ident $($tail:ident)+) => {
+        impl <$head, $($tail),+> Choice for ($head, $($tail),+)
+            where $head: Parser,
+                  $($tail: Parser<Input=<$head as Parser>::Input, Output=<$head as Parser>::Output>),+ {
+
+            type Input = <$head as Parser>::Input;
+            type Output = <$head as Parser>::Output;
+            #[allow(non_snake_case)]
+            fn choice_parse(&mut self, input: <Self as Choice>::Input) -> ParseResult<<Self as Choice>::Output, <Self as Choice>::Input> {
+                let (ref mut $head, $(ref mut $tail),+) = *self;
+                let mut err = match $head.parse(input.clone()) {
+                    Ok(x) => return Ok(x),
+                    Err(Consumed(err)) => return Err(Consumed(err)),
+                    Err(Empty(err)) => err
+                };
+                $(
+                    err = match $tail.parse(input.clone()) {
+                        Ok(x) => return Ok(x),
+                        Err(Consumed(err)) => return Err(Consumed(err)),
+                        Err(Empty(err2)) => err.merge(err2)
+                    };
+                )+
+                Err(Empty(err))
+            }
+            #[allow(non_snake_case)]
+            fn choice_representation(&self) -> Vec<Representation> {
+                let (ref $head, $(ref $tail),+) = *self;
+                let mut reprs = vec![$head.representation()];
+                $(reprs.push($tail.representation());)+
+                reprs
+            }
+        }
+        tuple_choice!($($tail)+);
+    };
+    ($only:ident) => {};
+}
+tuple_choice!(P1 P2 P3 P4 P5 P6 P7 P8);
+
+pub struct ChoiceParser<C>(C);
+impl <C: Choice> Parser for ChoiceParser<C> {
+    type Input = <C as Choice>::Input;
+    type Output = <C as Choice>::Output;
+    fn parse(&mut self, input: <Self as Parser>::Input) -> ParseResult<<Self as Parser>::Output, <Self as Parser>::Input> {
+        self.0.choice_parse(input)
+    }
+    fn representation(&self) -> Representation {
+        Representation::Choice(self.0.choice_representation())
+    }
+}
+pub fn choice<C: Choice>(parsers: C) -> ChoiceParser<C> {
+    ChoiceParser(parsers)
+}
+
 #[derive(Clone)]
 pub struct Optional<P>(P);
 impl <P> Parser for Optional<P>
@@ -256,10 +710,17 @@ impl <P> Parser for Optional<P>
     type Output = Option<<P as Parser>::Output>;
     fn parse(&mut self, input: <P as Parser>::Input) -> ParseResult<Option<<P as Parser>::Output>, <P as Parser>::Input> {
         match self.0.parse(input.clone()) {
-            Ok((x, rest)) => Ok((Some(x), rest)),
-            Err(_) => Ok((None, input))
+            Ok(Consumed((x, rest))) => Ok(Consumed((Some(x), rest))),
+            Ok(Empty((x, rest))) => Ok(Empty((Some(x), rest))),
+            // Only an `Empty` failure can be swallowed for free; a `Consumed` failure
+            // means the parser committed to this branch and must be allowed to fail hard.
+            Err(Consumed(err)) => Err(Consumed(err)),
+            Err(Empty(_)) => Ok(Empty((None, input)))
         }
     }
+    fn representation(&self) -> Representation {
+        Representation::Optional(Box::new(self.0.representation()))
+    }
 }
 pub fn optional<P>(parser: P) -> Optional<P> {
     Optional(parser)
@@ -267,40 +728,134 @@ pub fn optional<P>(parser: P) -> Optional<P> {
 
 
 pub struct Env<I> {
-    input: I
+    input: I,
+    consumed: bool
 }
 
 impl <I: Clone + Stream> Env<I> {
     pub fn new(input: I) -> Env<I> {
-        Env { input: input }
+        Env { input: input, consumed: false }
     }
-    
-    pub fn with<P, O>(&mut self, mut parser: P) -> Result<O, Error>
+
+    pub fn with<P, O>(&mut self, mut parser: P) -> Result<O, Consumed<PError<I>>>
         where P: Parser<Input=I, Output=O> {
-        let (o, rest) = try!(parser.parse(self.input.clone()));
-        self.input = rest;
-        Ok(o)
+        match parser.parse(self.input.clone()) {
+            Ok(Consumed((o, rest))) => {
+                self.input = rest;
+                self.consumed = true;
+                Ok(o)
+            }
+            Ok(Empty((o, rest))) => {
+                self.input = rest;
+                Ok(o)
+            }
+            Err(err) => Err(err)
+        }
     }
 
     pub fn result<O>(self, output: O) -> ParseResult<O, I> {
-        Ok((output, self.input))
+        if self.consumed { Ok(Consumed((output, self.input))) } else { Ok(Empty((output, self.input))) }
     }
 }
 
-pub fn digit<'a, I>(input: I) -> ParseResult<char, I>
+pub struct Digit<I> { _marker: PhantomData<I> }
+impl <I> Clone for Digit<I> {
+    fn clone(&self) -> Digit<I> { Digit { _marker: PhantomData } }
+}
+impl <I> Parser for Digit<I>
     where I: Stream<Item=char> + Clone {
-    match input.uncons() {
-        Ok((c, rest)) => {
-            if c.is_digit(10) { Ok((c, rest)) }
-            else { Err(Error) }
+    type Input = I;
+    type Output = char;
+    fn parse(&mut self, input: I) -> ParseResult<char, I> {
+        let position = input.position();
+        match input.uncons() {
+            Ok(Consumed((c, rest))) => {
+                if c.is_digit(10) { Ok(Consumed((c, rest))) }
+                else { Err(Empty(ParseError::new(position, ErrorInfo::Expected("digit".to_string())))) }
+            }
+            Err(err) => Err(err)
+        }
+    }
+    fn representation(&self) -> Representation {
+        Representation::CharClass("digit".to_string())
+    }
+}
+pub fn digit<I>() -> Digit<I>
+    where I: Stream<Item=char> + Clone {
+    Digit { _marker: PhantomData }
+}
+
+#[derive(Clone)]
+pub struct Named<P> { parser: P, name: &'static str }
+impl <P: Parser> Parser for Named<P> {
+    type Input = <P as Parser>::Input;
+    type Output = <P as Parser>::Output;
+    fn parse(&mut self, input: <P as Parser>::Input) -> ParseResult<<P as Parser>::Output, <P as Parser>::Input> {
+        self.parser.parse(input)
+    }
+    fn representation(&self) -> Representation {
+        Representation::Named(self.name.to_string(), Box::new(self.parser.representation()))
+    }
+}
+pub fn name<P: Parser>(parser: P, name: &'static str) -> Named<P> {
+    Named { parser: parser, name: name }
+}
+
+impl <P: Parser> Named<P> {
+    pub fn to_ebnf(&self) -> String {
+        let mut seen = Vec::new();
+        let mut rules = Vec::new();
+        render_rule(self.name, &self.parser.representation(), &mut seen, &mut rules);
+        rules.connect("\n")
+    }
+}
+
+fn render_rule(name: &str, repr: &Representation, seen: &mut Vec<String>, rules: &mut Vec<String>) {
+    if seen.iter().any(|n| &**n == name) { return; }
+    seen.push(name.to_string());
+    let expr = render_expr(repr, seen, rules);
+    rules.push(format!("{} = {} ;", name, expr));
+}
+
+fn render_expr(repr: &Representation, seen: &mut Vec<String>, rules: &mut Vec<String>) -> String {
+    match *repr {
+        Representation::StringTerminal(ref s) => format!("\"{}\"", s),
+        Representation::CharClass(ref s) => s.clone(),
+        Representation::Nonterminal(ref s) => s.clone(),
+        Representation::Named(ref n, ref body) => {
+            render_rule(n, &**body, seen, rules);
+            n.clone()
+        }
+        Representation::Sequence(ref rs) => {
+            rs.iter().map(|r| render_expr(r, seen, rules)).collect::<Vec<_>>().connect(" ")
+        }
+        Representation::Choice(ref rs) => {
+            rs.iter().map(|r| render_expr(r, seen, rules)).collect::<Vec<_>>().connect(" | ")
+        }
+        Representation::Repeat0(ref r) => format!("{{ {} }}", render_expr(&**r, seen, rules)),
+        Representation::Repeat1(ref r) => {
+            let e = render_expr(&**r, seen, rules);
+            format!("{} {{ {} }}", e, e)
+        }
+        Representation::Optional(ref r) => format!("[ {} ]", render_expr(&**r, seen, rules)),
+        Representation::SepBy(ref r, ref s) => {
+            let item = render_expr(&**r, seen, rules);
+            let sep = render_expr(&**s, seen, rules);
+            format!("[ {} {{ {} {} }} ]", item, sep, item)
         }
-        Err(err) => Err(err)
     }
 }
 
 pub trait ParserExt {
     fn and_then<P>(self, P) -> AndThen<Self, P>
         where P: Parser;
+    fn or<P>(self, P) -> Or<Self, P>
+        where P: Parser;
+    fn map<F, B>(self, F) -> Map<Self, F>
+        where F: FnMut(<Self as Parser>::Output) -> B;
+    fn then<F, P2>(self, F) -> Then<Self, F>
+        where F: FnMut(<Self as Parser>::Output) -> P2, P2: Parser<Input=<Self as Parser>::Input>;
+    fn name(self, &'static str) -> Named<Self>;
 }
 
 impl <P: Parser> ParserExt for P {
@@ -308,51 +863,162 @@ impl <P: Parser> ParserExt for P {
         where P2: Parser {
         and_then(self, p)
     }
+
+    fn or<P2>(self, p: P2) -> Or<Self, P2>
+        where P2: Parser {
+        or(self, p)
+    }
+
+    fn map<F, B>(self, f: F) -> Map<Self, F>
+        where F: FnMut(<Self as Parser>::Output) -> B {
+        map(self, f)
+    }
+
+    fn then<F, P2>(self, f: F) -> Then<Self, F>
+        where F: FnMut(<Self as Parser>::Output) -> P2, P2: Parser<Input=<Self as Parser>::Input> {
+        then(self, f)
+    }
+
+    fn name(self, n: &'static str) -> Named<Self> {
+        name(self, n)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+    use std::collections::HashMap;
+
+    fn ok<O, I>(result: ParseResult<O, I>) -> (O, I) {
+        result.ok().expect("parse failed").into_inner()
+    }
 
     fn integer<'a, I>(input: I) -> ParseResult<i64, I>
         where I: Stream<Item=char> + Clone {
-        let mut env = Env::new(input);
-        let chars = try!(env.with(many(digit as fn(_) -> _)));
-        let mut n = 0;
-        for &c in chars.iter() {
-            n = n * 10 + (c as i64 - '0' as i64);
-        }
-        env.result(n)
+        many1::<Vec<char>, _>(digit())
+            .map(|ds: Vec<char>| ds.into_iter().fold(0i64, |n, c| n * 10 + (c as i64 - '0' as i64)))
+            .parse(input)
     }
 
     #[test]
     fn test_integer() {
-        assert_eq!((integer as fn(_) -> _).parse("123"), Ok((123i64, "")));
+        assert_eq!(ok((integer as fn(_) -> _).parse("123")), (123i64, ""));
     }
     #[test]
     fn list() {
-        let mut p = sep_by(integer as fn(_) -> _, satisfy(|c| c == ','));
-        assert_eq!(p.parse("123,4,56"), Ok((vec![123, 4, 56], "")));
+        let mut p = sep_by::<Vec<i64>, _, _>(integer as fn(_) -> _, satisfy(|c| c == ','));
+        assert_eq!(ok(p.parse("123,4,56")), (vec![123, 4, 56], ""));
+    }
+    #[test]
+    fn sep_by_reports_a_hard_error_instead_of_silently_truncating() {
+        // The separator commits us to another item, so a bad item after it must be a
+        // hard error, not a reason to stop the loop and quietly return what we have.
+        let mut p = sep_by::<Vec<i64>, _, _>(integer as fn(_) -> _, satisfy(|c| c == ','));
+        assert!(p.parse("1,2,x").is_err());
     }
     #[test]
     fn iterator() {
-        let result = (integer as fn(_) -> _).parse("123".chars())
-            .map(|(i, mut iter)| (i, iter.next()));
-        assert_eq!(result, Ok((123i64, None)));
+        let (i, mut iter) = ok((integer as fn(_) -> _).parse("123".chars()));
+        assert_eq!((i, iter.next()), (123i64, None));
     }
     #[test]
     fn field() {
-        let word = many(satisfy(|c| c.is_alphanumeric()));
-        let word2 = many(satisfy(|c| c.is_alphanumeric()));
-        let spaces = many(space());
-        let c_decl = word
-            .and_then(spaces.clone())
-            .and_then(satisfy(|c| c == ':'))
-            .and_then(spaces)
-            .and_then(word2)
-            .parse("x: int")
-            .map(|(((((ret, _), _), _), name), rest)| ((ret, name), rest));
-        assert_eq!(c_decl, Ok(((vec!['x'], vec!['i', 'n', 't']), "")));
-    }
-}
\ No newline at end of file
+        // `.then` threads the parsed name through to the end without ever having to
+        // destructure a nested tuple of discarded intermediate results, and `many`
+        // collects straight into a `String` instead of a `Vec<char>`.
+        let mut p = many::<String, _>(satisfy(|c: char| c.is_alphanumeric())).then(|name: String| {
+            many::<String, _>(space())
+                .then(|_: String| satisfy(|c| c == ':'))
+                .then(|_: char| many::<String, _>(space()))
+                .then(move |_: String| many::<String, _>(satisfy(|c: char| c.is_alphanumeric())))
+                .map(move |rest: String| (name.clone(), rest))
+        });
+        let (output, rest) = ok(p.parse("x: int"));
+        let (ret, name) = output;
+        assert_eq!((ret, name, rest), ("x".to_string(), "int".to_string(), ""));
+    }
+
+    #[test]
+    fn tracks_source_position() {
+        let input: State<&str, SourcePosition> = State::new("ab\ncd");
+        let (_, input) = ok(input.uncons());
+        let (_, input) = ok(input.uncons());
+        let (_, input) = ok(input.uncons());
+        assert_eq!(input.position(), SourcePosition { line: 2, column: 1 });
+    }
+
+    #[test]
+    fn unexpected_char_reports_position() {
+        let input: State<&str, SourcePosition> = State::new("1a");
+        let (_, rest) = ok(digit().parse(input));
+        let err = digit().parse(rest).err().expect("a is not a digit").into_inner();
+        assert_eq!(err.position, SourcePosition { line: 1, column: 2 });
+        assert_eq!(err.errors, vec![ErrorInfo::Expected("digit".to_string())]);
+    }
+
+    #[test]
+    fn optional_does_not_backtrack_past_a_consumed_failure() {
+        // `string("let")` fails after matching "le", so `optional` must propagate the
+        // error rather than silently rewinding to the start of the input.
+        let mut p = optional(string::<&str>("let"));
+        assert!(p.parse("lex").is_err());
+    }
+
+    #[test]
+    fn or_tries_the_second_alternative() {
+        let mut p = string::<&str>("true").or(string("false"));
+        assert_eq!(ok(p.parse("false")), ("false", ""));
+    }
+
+    #[test]
+    fn or_commits_to_the_first_alternative_once_it_consumes() {
+        // "let" matches the first two characters of "lex", so the first branch commits
+        // and the second branch never gets a chance to run.
+        let mut p = string::<&str>("let").or(string("lex"));
+        assert!(p.parse("lex").is_err());
+    }
+
+    #[test]
+    fn choice_dispatches_over_a_tuple_of_parsers() {
+        let mut p = choice((string::<&str>("true"), string("false"), string("null")));
+        assert_eq!(ok(p.parse("null")), ("null", ""));
+    }
+
+    #[test]
+    fn sep_by_collects_into_a_hash_map() {
+        let pair = satisfy(|c: char| c.is_alphabetic())
+            .and_then(satisfy(|c| c == '='))
+            .and_then(satisfy(|c: char| c.is_alphanumeric()))
+            .map(|((k, _), v)| (k, v));
+        let mut p = sep_by::<HashMap<char, char>, _, _>(pair, satisfy(|c| c == ','));
+        let (map, rest) = ok(p.parse("a=1,b=2"));
+        assert_eq!(map.get(&'a'), Some(&'1'));
+        assert_eq!(map.get(&'b'), Some(&'2'));
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn named_parser_renders_its_grammar_as_ebnf() {
+        let p = name(many1::<Vec<char>, _>(digit::<&str>()), "integer");
+        assert_eq!(p.to_ebnf(), "integer = digit { digit } ;".to_string());
+    }
+
+    #[test]
+    fn to_ebnf_renders_a_choice_of_string_terminals() {
+        let p = name(string::<&str>("true").or(string("false")), "boolean");
+        assert_eq!(p.to_ebnf(), "boolean = \"true\" | \"false\" ;".to_string());
+    }
+
+    #[test]
+    fn to_ebnf_emits_a_production_for_every_named_rule_it_nests_and_dedupes_repeats() {
+        let integer = name(many1::<Vec<char>, _>(digit::<&str>()), "integer");
+        let p = name(
+            integer.clone().and_then(satisfy(|c| c == ',')).and_then(integer),
+            "pair"
+        );
+        assert_eq!(
+            p.to_ebnf(),
+            "integer = digit { digit } ;\npair = integer char integer ;".to_string()
+        );
+    }
+}